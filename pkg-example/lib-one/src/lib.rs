@@ -33,6 +33,9 @@ pub struct Config {
 
     #[default(OtherChoice::Foo)]
     other_choice: OtherChoice,
+
+    #[default([1, 2, 3])]
+    retry_backoffs: [u32; 3],
 }
 
 impl Config {
@@ -4,6 +4,8 @@
 //!
 //! * Crates can declare variables that can be overridden
 //!     * Anything const, e.g. usize, strings, etc.
+//!     * Fixed-size arrays of any of the above, e.g. `[u32; 3]`
+//!     * Or, opting into `#[toml_cfg::toml_config(serde)]`, any `serde::Deserialize` type
 //! * (Only) The "root crate" can override these variables by including a `cfg.toml` file
 //!
 //! ## Config file
@@ -52,6 +54,104 @@
 //!
 //! If you *do* find a case where this occurs, please open an issue!
 //!
+//! ## Environment variable overrides
+//!
+//! Any field can also be overridden at build time with an environment
+//! variable, without touching `cfg.toml`. The variable name is built the
+//! same way Cargo builds its own config env vars: uppercase the crate name
+//! and the field name, and replace any `-` with `_`:
+//!
+//! ```shell
+//! # Override `buffer_size` for crate `lib-one`
+//! $ TOML_CFG_LIB_ONE_BUFFER_SIZE=8192 cargo build
+//! ```
+//!
+//! Environment variables take precedence over `cfg.toml`, which in turn
+//! takes precedence over the `#[default(...)]` value. The override string
+//! is parsed as a TOML value, so numbers, strings, and enum variant names
+//! all work exactly as they would if written into `cfg.toml`.
+//!
+//! ## Layered `cfg.toml` (monorepos)
+//!
+//! Starting from the crate being compiled, `toml-cfg` walks up the
+//! directory tree to the "root project" path, reading a `cfg.toml` at
+//! every level that has one. This mirrors how Cargo itself merges
+//! `config.toml` files found in ancestor directories. If the crate being
+//! compiled doesn't live under the "root project" path (e.g. a dependency
+//! stored elsewhere), every one of its ancestor directories is checked and
+//! the root project's own `cfg.toml` is then read as the outermost layer.
+//!
+//! ```toml
+//! # <workspace root>/cfg.toml
+//! [lib-one]
+//! buffer_size = 4096
+//! greeting = "hi"
+//!
+//! # <workspace root>/apps/my-app/cfg.toml
+//! [lib-one]
+//! greeting = "Guten tag!"
+//! ```
+//!
+//! Keys are merged per-crate, and by default the file closer to the crate
+//! being built wins for any key it sets, falling back to the value from a
+//! file further up the tree for keys it leaves unset. In the example
+//! above, `lib-one` ends up with `buffer_size = 4096` from the
+//! workspace-root file and `greeting = "Guten tag!"` from the app-local
+//! file. Set `TOML_CFG=prefer_root` to invert this and let the file
+//! closest to the root win instead.
+//!
+//! Two situations are treated as outright ambiguous and fail the build
+//! rather than silently picking a winner: a single directory defining both
+//! `cfg.toml` and `cfg.local.toml` (the latter isn't itself a supported
+//! config source — it's only checked for so it can be flagged here instead
+//! of silently ignored), and, when `TOML_CFG=strict_sources` is set, two
+//! layers setting the same key to conflicting values.
+//!
+//! ## Struct-valued config via `serde`
+//!
+//! The default mode resolves each field independently, splicing its
+//! resolved value in as a literal, which only works for const-constructible
+//! types. `#[toml_cfg::toml_config(serde)]` instead derives
+//! `serde::Deserialize` on the struct and deserializes the whole
+//! `[crate-name]` table into it at once, so fields can be arbitrary
+//! `Deserialize` types: nested structs, `Option<T>`, maps, and so on.
+//!
+//! ```rust
+//! #[derive(serde::Deserialize, Debug, Clone)]
+//! pub struct Nested {
+//!     pub retries: u32,
+//! }
+//!
+//! #[toml_cfg::toml_config(serde)]
+//! pub struct Config {
+//!     #[default(Nested { retries: 3 })]
+//!     nested: Nested,
+//! }
+//!
+//! // `Config` can't be a `const` here (`serde` deserialization isn't
+//! // possible in a const context), so it's resolved once and cached:
+//! let cfg = Config::config();
+//! ```
+//!
+//! Per-field `TOML_CFG_*` env var overrides aren't available in this mode.
+//! Fields must be owned types (`toml::from_str` requires `DeserializeOwned`),
+//! so use `String` rather than `&'static str`.
+//!
+//! ## Diagnostics
+//!
+//! If a `cfg.toml`/env value is the wrong kind of value for the field's
+//! declared type (a string for an integer field, an array for a scalar
+//! field, ...), the error names exactly where it came from:
+//!
+//! ```text
+//! cfg.toml:12: key 'buffer_size' for crate 'lib-one' has value "big" but field type is usize
+//! ```
+//!
+//! Other mismatches, such as an integer out of range for the field's type
+//! or a string that names no variant of the field's enum, are not caught
+//! here: they are reported by the compiler against the generated code,
+//! without the file, line or key.
+//!
 //! ## Look at what we get!
 //!
 //! ```shell
@@ -86,7 +186,7 @@
 //! ```
 //!
 
-use heck::ToShoutySnekCase;
+use heck::{ToShoutySnekCase, ToSnekCase};
 use proc_macro::TokenStream;
 use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
 use quote::{format_ident, quote, ToTokens};
@@ -107,8 +207,100 @@ struct Defn {
     vals: HashMap<String, toml::Value>,
 }
 
+// Where a resolved value came from, for error messages: the `cfg.toml` it
+// was read from (with a line number) or the env var that set it.
+#[derive(Clone, Debug)]
+struct Located {
+    value: toml::Value,
+    origin: String,
+}
+
+// The fully-merged, per-crate config: same shape as `Defn::vals`, but with
+// each value's origin resolved into a human-readable string.
+#[derive(Clone, Debug, Default)]
+struct ResolvedCfg {
+    vals: HashMap<String, Located>,
+}
+
+// 1-based line number of `key`'s definition within the `[crate_name]` table
+// of `contents`, or `1` if it can't be found (e.g. an inline table). We
+// deserialize values through `#[serde(flatten)]` into a plain `toml::Value`
+// map (no span-tracking wrapper survives that flatten), so provenance is
+// recovered by re-scanning the source text for the table and key instead.
+fn find_key_line(contents: &str, crate_name: &str, key: &str) -> usize {
+    let header = format!("[{}]", crate_name);
+    let mut in_table = false;
+    for (idx, line) in contents.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            in_table = trimmed == header;
+            continue;
+        }
+        if in_table {
+            if let Some(eq) = trimmed.find('=') {
+                if trimmed[..eq].trim() == key {
+                    return idx + 1;
+                }
+            }
+        }
+    }
+    1
+}
+
+// `toml::Value`'s own `FromStr` parses a whole TOML *document*, not a bare
+// value, so `"8192".parse::<toml::Value>()` fails (it looks like an
+// incomplete `key = value` line). Parse env var overrides by wrapping the
+// string as the right-hand side of a throwaway key instead, which lets
+// numbers, bools, and arrays parse exactly as they would in `cfg.toml`.
+// Anything that still doesn't parse (e.g. a bare enum variant name like
+// `Foo`) is treated as a plain TOML string.
+fn parse_env_value(s: &str) -> toml::Value {
+    #[derive(Deserialize)]
+    struct Wrapper {
+        v: toml::Value,
+    }
+
+    let wrapped = format!("v = {}\n", s);
+    if let Ok(w) = toml::from_str::<Wrapper>(&wrapped) {
+        return w.v;
+    }
+
+    let quoted = format!("v = {:?}\n", s);
+    toml::from_str::<Wrapper>(&quoted)
+        .unwrap_or_else(|e| {
+            panic!(
+                "Failed to parse env var value `{}` as a valid TOML value: {}",
+                s, e
+            )
+        })
+        .v
+}
+
+// The `#[default(...)]` attribute declared on `field`, shared by both
+// codegen modes below.
+fn find_default_attr(field: &syn::Field) -> syn::Attribute {
+    let ident = field
+        .ident
+        .as_ref()
+        .expect("Failed to find field identifier. Don't use this on a tuple struct.");
+
+    field
+        .attrs
+        .iter()
+        .find(|a| a.path.get_ident() == Some(&Ident::new("default", Span::call_site())))
+        .unwrap_or_else(|| {
+            panic!(
+                "Failed to find `#[default(...)]` attribute for field `{}`.",
+                ident
+            )
+        })
+        .clone()
+}
+
 #[proc_macro_attribute]
-pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn toml_config(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let serde_mode = attr.to_string().trim() == "serde";
+
     let struct_defn =
         syn::parse::<syn::ItemStruct>(item).expect("Failed to parse configuration structure!");
 
@@ -119,25 +311,29 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
     };
 
     let root_path = find_root_path();
-    let cfg_path = root_path.clone();
-    let cfg_path = cfg_path.as_ref().and_then(|c| {
-        let mut x = c.to_owned();
-        x.push("cfg.toml");
-        Some(x)
-    });
-
-    let maybe_cfg = cfg_path.as_ref().and_then(|c| load_crate_cfg(&c));
-    let got_cfg = maybe_cfg.is_some();
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").ok().map(PathBuf::from);
+
+    let (cfg, got_cfg, cfg_paths) = match (&manifest_dir, &root_path) {
+        (Some(manifest_dir), Some(root_path)) => load_layered_cfg(manifest_dir, root_path),
+        _ => (ResolvedCfg::default(), false, Vec::new()),
+    };
+
     if require_cfg_present {
         assert!(
             got_cfg,
             "TOML_CFG=require_cfg_present set, but valid config not found!"
         )
     }
-    let cfg = maybe_cfg.unwrap_or_else(|| Defn::default());
+
+    let crate_name = env::var("CARGO_PKG_NAME").unwrap_or_default();
+
+    if serde_mode {
+        return gen_serde_config(struct_defn, &cfg, &cfg_paths).into();
+    }
 
     let mut struct_defn_fields = TokenStream2::new();
     let mut struct_inst_fields = TokenStream2::new();
+    let mut env_retriggers = TokenStream2::new();
 
     for field in struct_defn.fields {
         let ident = field
@@ -146,46 +342,37 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
             .expect("Failed to find field identifier. Don't use this on a tuple struct.");
 
         // Determine the default value, declared using the `#[default(...)]` syntax
-        let default = field
-            .attrs
-            .iter()
-            .find(|a| a.path.get_ident() == Some(&Ident::new("default", Span::call_site())))
-            .expect(&format!(
-                "Failed to find `#[default(...)]` attribute for field `{}`.",
-                ident.to_string(),
-            ))
-            .clone();
+        let default = find_default_attr(&field);
 
         let ty = field.ty;
 
-        // Is this field overridden?
-        let val = match cfg.vals.get(&ident.to_string()) {
-            Some(t) => {
-                let t_string = t.to_string();
-                let value: TokenStream2 = t_string.parse().expect(&format!(
-                    "Failed to parse `{}` as a valid token!",
-                    &t_string
-                ));
-
-                let default_value = default.tokens.to_string();
-
-                let is_enum = default_value.contains("::")
-                    && default_value
-                        .starts_with(&format!("({} ::", ty.to_token_stream().to_string()));
-
-                if is_enum {
-                    let value_string = format_ident!(
-                        "{}",
-                        t.as_str().expect(&format!(
-                            "Failed to parse `{}` as a valid string!",
-                            &t_string
-                        ))
-                    );
-                    quote! { #ty::#value_string }
-                } else {
-                    quote! { #value }
-                }
-            }
+        // The env var is named after Cargo's own convention: uppercase the
+        // crate name and field name, joined by `_`, with `-` treated as `_`.
+        let env_var_name = format!(
+            "TOML_CFG_{}_{}",
+            crate_name.TO_SHOUTY_SNEK_CASE(),
+            ident.to_string().TO_SHOUTY_SNEK_CASE(),
+        );
+
+        // Proc macros aren't automatically re-run when an env var changes, so
+        // emit a use of `option_env!` for it. rustc tracks env vars read this
+        // way and will retrigger compilation when they change.
+        quote! {
+            const _: Option<&'static str> = option_env!(#env_var_name);
+        }
+        .to_tokens(&mut env_retriggers);
+
+        let env_override = env::var(&env_var_name).ok().map(|s| Located {
+            value: parse_env_value(&s),
+            origin: format!("env var `{}`", env_var_name),
+        });
+
+        // Is this field overridden, either by an env var or by `cfg.toml`?
+        // The env var wins if both are present.
+        let overridden = env_override.or_else(|| cfg.vals.get(&ident.to_string()).cloned());
+
+        let val = match overridden {
+            Some(loc) => value_to_tokens(&ty, &ident.to_string(), &crate_name, &loc),
             None => {
                 let default = &default.tokens;
                 quote! { #default }
@@ -210,14 +397,14 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
         .parse()
         .expect("NO NOT THE SHOUTY SNAKE");
 
-    let hack_retrigger = if let Some(cfg_path) = cfg_path {
+    let mut hack_retrigger = TokenStream2::new();
+    for cfg_path in &cfg_paths {
         let cfg_path = format!("{}", cfg_path.display());
         quote! {
             const _: &[u8] = include_bytes!(#cfg_path);
         }
-    } else {
-        quote! {}
-    };
+        .to_tokens(&mut hack_retrigger);
+    }
 
     quote! {
         pub struct #struct_ident {
@@ -230,16 +417,425 @@ pub fn toml_config(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
         mod toml_cfg_hack {
             #hack_retrigger
+            #env_retriggers
         }
     }
     .into()
 }
 
-fn load_crate_cfg(path: &Path) -> Option<Defn> {
-    let contents = std::fs::read_to_string(&path).ok()?;
-    let parsed = toml::from_str::<Config>(&contents).ok()?;
-    let name = env::var("CARGO_PKG_NAME").ok()?;
-    parsed.crates.get(&name).cloned()
+// The alternative, `#[toml_cfg::toml_config(serde)]` generation mode:
+// instead of resolving each field independently through literal-token
+// splicing, derive `serde::Deserialize` on the struct and deserialize the
+// whole merged `[crate-name]` table into it in one shot. This is what lets
+// fields be arbitrary `Deserialize` types (nested structs, `Option<T>`,
+// maps, ...), at the cost of the result no longer being a `const`: `serde`
+// deserialization can't run in a const context, so the value is resolved
+// once, the first time it's accessed, via a `OnceLock`, rather than being
+// a compile-time constant the way the default mode's output is. Per-field
+// env var overrides (`TOML_CFG_*`) aren't supported in this mode; only
+// `cfg.toml` is consulted.
+fn gen_serde_config(
+    struct_defn: syn::ItemStruct,
+    cfg: &ResolvedCfg,
+    cfg_paths: &[PathBuf],
+) -> TokenStream2 {
+    let mut serde_fields = TokenStream2::new();
+    let mut default_fns = TokenStream2::new();
+
+    for field in struct_defn.fields {
+        let ident = field
+            .ident
+            .clone()
+            .expect("Failed to find field identifier. Don't use this on a tuple struct.");
+
+        let default = find_default_attr(&field);
+
+        let ty = field.ty;
+
+        // `Config::config()` deserializes via `toml::from_str`, which requires
+        // `DeserializeOwned` — a borrowed field like `&'static str` can't
+        // satisfy that (there's no buffer for it to borrow from), and fails
+        // with an opaque serde error pointing nowhere near the real cause.
+        // Catch it here instead, while we still know which field it is.
+        if matches!(ty, syn::Type::Reference(_)) {
+            panic!(
+                "Field `{}` is a reference type (`{}`), which isn't supported by \
+                 `#[toml_cfg::toml_config(serde)]`: deserializing requires an owned \
+                 type (e.g. `String` instead of `&'static str`).",
+                ident,
+                ty.to_token_stream(),
+            );
+        }
+
+        let default_tokens = &default.tokens;
+        let default_fn_ident = format_ident!("__toml_cfg_default_{}", ident);
+        let default_fn_name = default_fn_ident.to_string();
+
+        quote! {
+            fn #default_fn_ident() -> #ty {
+                #default_tokens
+            }
+        }
+        .to_tokens(&mut default_fns);
+
+        quote! {
+            #[serde(default = #default_fn_name)]
+            pub #ident: #ty,
+        }
+        .to_tokens(&mut serde_fields);
+    }
+
+    let struct_ident = struct_defn.ident;
+
+    // The merged config, serialized back to TOML text so the struct can be
+    // deserialized from it at runtime. `toml::Value`'s `Display` prints each
+    // value as a valid TOML value literal, so `key = <value>` lines are all
+    // that's needed; keys absent here fall back to `#[serde(default = ...)]`.
+    let mut toml_text = String::new();
+    for (key, located) in &cfg.vals {
+        toml_text.push_str(&format!("{} = {}\n", key, located.value));
+    }
+
+    let accessor = format_ident!("{}", struct_ident.to_string().to_snek_case());
+
+    let mut hack_retrigger = TokenStream2::new();
+    for cfg_path in cfg_paths {
+        let cfg_path = format!("{}", cfg_path.display());
+        quote! {
+            const _: &[u8] = include_bytes!(#cfg_path);
+        }
+        .to_tokens(&mut hack_retrigger);
+    }
+
+    quote! {
+        #[derive(serde::Deserialize, Debug, Clone)]
+        pub struct #struct_ident {
+            #serde_fields
+        }
+
+        #default_fns
+
+        impl #struct_ident {
+            pub fn #accessor() -> &'static #struct_ident {
+                static CFG_TOML: &str = #toml_text;
+                static CELL: std::sync::OnceLock<#struct_ident> = std::sync::OnceLock::new();
+                CELL.get_or_init(|| {
+                    toml::from_str(CFG_TOML).expect("Failed to deserialize crate config via serde")
+                })
+            }
+        }
+
+        mod toml_cfg_hack {
+            #hack_retrigger
+        }
+    }
+}
+
+// The handful of primitive kinds we can validate a TOML value against
+// before ever emitting tokens. Anything else (enums, newtypes, ...) is
+// trusted and handled by the fallback paths below.
+#[derive(PartialEq, Eq)]
+enum PrimitiveKind {
+    Integer,
+    Float,
+    Bool,
+}
+
+fn primitive_kind(ty: &syn::Type) -> Option<PrimitiveKind> {
+    match ty.to_token_stream().to_string().replace(' ', "").as_str() {
+        "usize" | "isize" | "u8" | "u16" | "u32" | "u64" | "u128" | "i8" | "i16" | "i32"
+        | "i64" | "i128" => Some(PrimitiveKind::Integer),
+        "f32" | "f64" => Some(PrimitiveKind::Float),
+        "bool" => Some(PrimitiveKind::Bool),
+        _ => None,
+    }
+}
+
+// Is `ty` a `&'static str`/`&str`/`String`, i.e. a type a bare TOML string
+// can be spliced into directly, rather than treated as an enum variant name?
+fn is_string_type(ty: &syn::Type) -> bool {
+    matches!(
+        ty.to_token_stream().to_string().replace(' ', "").as_str(),
+        "&'staticstr" | "&str" | "String"
+    )
+}
+
+// The element type of a `[T; N]` or `[T]` field, if `ty` is one of those
+// (not a reference to one — callers that care about `&'static [T]` peel the
+// reference off first so they know to re-add it around the array literal).
+fn array_elem_type(ty: &syn::Type) -> Option<&syn::Type> {
+    match ty {
+        syn::Type::Array(arr) => Some(&arr.elem),
+        syn::Type::Slice(slice) => Some(&slice.elem),
+        _ => None,
+    }
+}
+
+// An unsuffixed integer literal token for `i`. Splicing `i: &i64` straight
+// through `quote!` emits an `i64`-suffixed literal (`quote`'s `ToTokens` for
+// integers always picks a concrete type), which fails to typecheck against
+// any other integer field type (`usize`, `u32`, ...) — the common case.
+fn int_literal(i: i64) -> TokenStream2 {
+    syn::LitInt::new(&i.to_string(), Span::call_site()).into_token_stream()
+}
+
+// Same issue as `int_literal`, but for floats. `f64::to_string()` can come
+// back without a `.` (e.g. `4` for `4.0`), which isn't a valid float
+// literal token on its own, so make sure one is always present.
+fn float_literal(f: f64) -> TokenStream2 {
+    let mut s = f.to_string();
+    if !s.contains(['.', 'e', 'E']) {
+        s.push_str(".0");
+    }
+    syn::LitFloat::new(&s, Span::call_site()).into_token_stream()
+}
+
+// Panic with a diagnostic naming the file (and line) the bad value came
+// from, the key and crate it belongs to, its actual value, and the field
+// type it was supposed to satisfy, e.g.:
+// `cfg.toml:12: key 'buffer_size' for crate 'lib-one' has value "big" but field type is usize`
+fn type_mismatch(loc: &Located, key: &str, crate_name: &str, ty: &syn::Type) -> ! {
+    panic!(
+        "{}: key '{}' for crate '{}' has value {} but field type is {}",
+        loc.origin,
+        key,
+        crate_name,
+        loc.value,
+        ty.to_token_stream(),
+    )
+}
+
+// Convert an overriding value into tokens matching the field's declared
+// type, recursively, so that arrays (and arrays of arrays) work the same
+// way scalars do. A TOML string is only ever a plain `&str` / `String`
+// literal when the field is itself declared as one of those types; for any
+// other, non-primitive type it's treated as the name of an enum variant of
+// that type, since that's the only shape a `#[default(...)]` value for a
+// non-string, non-numeric field can take. Obvious mismatches (a string for
+// a `usize` field, an array for a scalar field, ...) are rejected with a
+// message pointing at exactly where the bad value came from.
+fn value_to_tokens(ty: &syn::Type, key: &str, crate_name: &str, loc: &Located) -> TokenStream2 {
+    let kind = primitive_kind(ty);
+
+    match &loc.value {
+        toml::Value::Integer(i) => match kind {
+            Some(PrimitiveKind::Integer) | None => int_literal(*i),
+            Some(PrimitiveKind::Float) => {
+                let i = int_literal(*i);
+                quote! { (#i as #ty) }
+            }
+            Some(PrimitiveKind::Bool) => type_mismatch(loc, key, crate_name, ty),
+        },
+        toml::Value::Float(f) => match kind {
+            Some(PrimitiveKind::Float) | None => float_literal(*f),
+            Some(PrimitiveKind::Integer) | Some(PrimitiveKind::Bool) => {
+                type_mismatch(loc, key, crate_name, ty)
+            }
+        },
+        toml::Value::Boolean(b) => match kind {
+            Some(PrimitiveKind::Bool) | None => quote! { #b },
+            Some(PrimitiveKind::Integer) | Some(PrimitiveKind::Float) => {
+                type_mismatch(loc, key, crate_name, ty)
+            }
+        },
+        toml::Value::String(s) => {
+            if kind.is_some() {
+                type_mismatch(loc, key, crate_name, ty)
+            } else if is_string_type(ty) {
+                quote! { #s }
+            } else {
+                let variant = format_ident!("{}", s);
+                quote! { #ty::#variant }
+            }
+        }
+        // `&'static [T]` fields need the array literal re-wrapped in a `&`;
+        // `[T; N]` fields don't. Peel the reference off first so
+        // `array_elem_type` only ever has to deal with the array/slice
+        // shape itself.
+        toml::Value::Array(items) => {
+            let (elem_ty, wrap_in_ref) = match ty {
+                syn::Type::Reference(r) => (array_elem_type(&r.elem), true),
+                _ => (array_elem_type(ty), false),
+            };
+
+            match elem_ty {
+                Some(elem_ty) => {
+                    let elems = items.iter().map(|item| {
+                        value_to_tokens(
+                            elem_ty,
+                            key,
+                            crate_name,
+                            &Located {
+                                value: item.clone(),
+                                origin: loc.origin.clone(),
+                            },
+                        )
+                    });
+                    if wrap_in_ref {
+                        quote! { &[ #(#elems),* ] }
+                    } else {
+                        quote! { [ #(#elems),* ] }
+                    }
+                }
+                None => type_mismatch(loc, key, crate_name, ty),
+            }
+        }
+        toml::Value::Datetime(_) | toml::Value::Table(_) => type_mismatch(loc, key, crate_name, ty),
+    }
+}
+
+// Does a `TOML_CFG=prefer_root` flag ask the root-most `cfg.toml` to win
+// ties, instead of the default (the file nearest the crate being built)?
+fn prefer_root() -> bool {
+    env::var("TOML_CFG")
+        .map(|v| v.contains("prefer_root"))
+        .unwrap_or(false)
+}
+
+// Does a `TOML_CFG=strict_sources` flag ask us to fail the build rather than
+// silently pick a winner when the same key is set to different values by
+// more than one layered `cfg.toml`?
+fn strict_sources() -> bool {
+    env::var("TOML_CFG")
+        .map(|v| v.contains("strict_sources"))
+        .unwrap_or(false)
+}
+
+// The one recognized config file name.
+const CFG_FILENAME: &str = "cfg.toml";
+
+// Not itself a loadable config source: only checked for here so a stray
+// `cfg.local.toml` next to `cfg.toml` (e.g. left over from some other tool's
+// convention) is flagged as ambiguous rather than silently ignored.
+const AMBIGUOUS_CFG_FILENAME: &str = "cfg.local.toml";
+
+// The config file that applies to `dir`, if any. Panics if `cfg.local.toml`
+// is also present there, inspired by jj's `AmbiguousSource` handling:
+// silently ignoring it would be surprising, so we ask the user to remove it
+// (or rename it to `cfg.toml`) instead.
+fn resolve_cfg_path(dir: &Path) -> Option<PathBuf> {
+    let cfg_path = dir.join(CFG_FILENAME);
+    let ambiguous_path = dir.join(AMBIGUOUS_CFG_FILENAME);
+
+    if cfg_path.is_file() && ambiguous_path.is_file() {
+        panic!(
+            "Ambiguous config sources in `{}`: found `{}` and `{}`. Keep only one.",
+            dir.display(),
+            cfg_path.display(),
+            ambiguous_path.display(),
+        );
+    }
+
+    cfg_path.is_file().then_some(cfg_path)
+}
+
+// The chain of directories from `start_dir` up to (and including) `root_dir`,
+// nearest-first. If `root_dir` isn't actually an ancestor of `start_dir` we
+// climb as far as the filesystem allows and then append `root_dir` as the
+// outermost layer.
+fn find_cfg_chain(start_dir: &Path, root_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![start_dir.to_owned()];
+    let mut cur = start_dir.to_owned();
+    while cur != root_dir {
+        match cur.parent() {
+            Some(parent) => {
+                cur = parent.to_owned();
+                dirs.push(cur.clone());
+            }
+            None => break,
+        }
+    }
+    if cur != root_dir {
+        dirs.push(root_dir.to_owned());
+    }
+    dirs
+}
+
+// Walk the directory chain from the crate being built up to the workspace
+// root, reading every `cfg.toml` found along the way, and merge their
+// values for the current crate into a single `ResolvedCfg`. Returns the
+// merged config, whether the current crate's table was found in at least
+// one of the files, and the list of files that were actually read (for the
+// `include_bytes!` recompile hack).
+fn load_layered_cfg(manifest_dir: &Path, root_dir: &Path) -> (ResolvedCfg, bool, Vec<PathBuf>) {
+    let crate_name = match env::var("CARGO_PKG_NAME") {
+        Ok(name) => name,
+        Err(_) => return (ResolvedCfg::default(), false, Vec::new()),
+    };
+
+    let mut chain = find_cfg_chain(manifest_dir, root_dir);
+    // `chain` is nearest-first. The layer merged *last* wins a given key, so
+    // to make the nearest file win by default we merge root-first.
+    if !prefer_root() {
+        chain.reverse();
+    }
+
+    let strict = strict_sources();
+    let mut merged = ResolvedCfg::default();
+    let mut found_crate = false;
+    let mut read_paths = Vec::new();
+    // Only tracked in strict mode: the file each already-merged key came
+    // from, so a same-key/different-value collision across layers can name
+    // both offending files instead of silently picking a winner.
+    let mut source_of: HashMap<String, PathBuf> = HashMap::new();
+    let mut conflicts: Vec<String> = Vec::new();
+
+    for dir in chain {
+        let path = match resolve_cfg_path(&dir) {
+            Some(path) => path,
+            None => continue,
+        };
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => continue,
+        };
+        read_paths.push(path.clone());
+
+        let parsed = match toml::from_str::<Config>(&contents) {
+            Ok(p) => p,
+            Err(_) => continue,
+        };
+
+        if let Some(defn) = parsed.crates.get(&crate_name) {
+            found_crate = true;
+            for (key, value) in &defn.vals {
+                let value = value.clone();
+
+                if strict {
+                    if let (Some(prev), Some(prev_path)) =
+                        (merged.vals.get(key), source_of.get(key))
+                    {
+                        if prev.value != value && prev_path != &path {
+                            conflicts.push(format!(
+                                "key '{}' for crate '{}' is set differently in `{}` and `{}`",
+                                key,
+                                crate_name,
+                                prev_path.display(),
+                                path.display(),
+                            ));
+                        }
+                    }
+                    source_of.insert(key.clone(), path.clone());
+                }
+
+                let origin = format!(
+                    "{}:{}",
+                    path.display(),
+                    find_key_line(&contents, &crate_name, key)
+                );
+                merged.vals.insert(key.clone(), Located { value, origin });
+            }
+        }
+    }
+
+    if !conflicts.is_empty() {
+        panic!(
+            "TOML_CFG=strict_sources: conflicting config sources:\n{}",
+            conflicts.join("\n")
+        );
+    }
+
+    (merged, found_crate, read_paths)
 }
 
 // From https://stackoverflow.com/q/60264534
@@ -268,3 +864,211 @@ fn find_root_path() -> Option<PathBuf> {
 
     Some(out_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn located(value: toml::Value) -> Located {
+        Located {
+            value,
+            origin: "test".to_string(),
+        }
+    }
+
+    // An integer override for a non-`i64` field must come out as a bare,
+    // unsuffixed literal: `quote!`'s blanket `ToTokens` for `i64` always
+    // suffixes (`4096i64`), which doesn't typecheck against `usize`/`u32`/etc.
+    #[test]
+    fn integer_override_has_no_type_suffix() {
+        for ty_str in ["usize", "u32", "u8", "i64"] {
+            let ty: syn::Type = syn::parse_str(ty_str).unwrap();
+            let tokens = value_to_tokens(&ty, "buffer_size", "lib-one", &located(4096.into()));
+            let expr: syn::Expr = syn::parse2(tokens).unwrap();
+            match expr {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Int(lit),
+                    ..
+                }) => {
+                    assert_eq!(lit.suffix(), "", "suffix leaked for {}", ty_str);
+                    assert_eq!(lit.base10_parse::<i64>().unwrap(), 4096);
+                }
+                other => panic!("expected an int literal for {}, got {:?}", ty_str, other),
+            }
+        }
+    }
+
+    // `[u32; 3]` fields: a `[1, 2, 3]` override becomes a bare array literal
+    // of unsuffixed integer literals.
+    #[test]
+    fn fixed_size_array_override() {
+        let ty: syn::Type = syn::parse_str("[u32; 3]").unwrap();
+        let value = toml::Value::Array(vec![1.into(), 2.into(), 3.into()]);
+        let tokens = value_to_tokens(&ty, "retry_backoffs", "lib-one", &located(value));
+        let expr: syn::Expr = syn::parse2(tokens).unwrap();
+        match expr {
+            syn::Expr::Array(arr) => {
+                assert_eq!(arr.elems.len(), 3);
+                for elem in &arr.elems {
+                    match elem {
+                        syn::Expr::Lit(syn::ExprLit {
+                            lit: syn::Lit::Int(lit),
+                            ..
+                        }) => assert_eq!(lit.suffix(), ""),
+                        other => panic!("expected an int literal element, got {:?}", other),
+                    }
+                }
+            }
+            other => panic!("expected an array literal, got {:?}", other),
+        }
+    }
+
+    // `&'static [u32]` fields need the array literal re-wrapped in a `&`,
+    // since `array_elem_type` only looks at the array/slice shape itself.
+    #[test]
+    fn slice_reference_override() {
+        let ty: syn::Type = syn::parse_str("&'static [u32]").unwrap();
+        let value = toml::Value::Array(vec![1.into(), 2.into()]);
+        let tokens = value_to_tokens(&ty, "retry_backoffs", "lib-one", &located(value));
+        let expr: syn::Expr = syn::parse2(tokens).unwrap();
+        match expr {
+            syn::Expr::Reference(r) => match *r.expr {
+                syn::Expr::Array(arr) => assert_eq!(arr.elems.len(), 2),
+                other => panic!(
+                    "expected an array literal under the reference, got {:?}",
+                    other
+                ),
+            },
+            other => panic!("expected a reference to an array literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn float_override_has_no_type_suffix() {
+        let ty: syn::Type = syn::parse_str("f32").unwrap();
+        let tokens = value_to_tokens(&ty, "ratio", "lib-one", &located(1.5.into()));
+        let expr: syn::Expr = syn::parse2(tokens).unwrap();
+        match expr {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Float(lit),
+                ..
+            }) => assert_eq!(lit.suffix(), ""),
+            other => panic!("expected a float literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn find_key_line_finds_key_in_its_table() {
+        let contents =
+            "[lib-two]\nbuffer_size = 1\n\n[lib-one]\ngreeting = \"hi\"\nbuffer_size = 4096\n";
+        assert_eq!(find_key_line(contents, "lib-one", "buffer_size"), 6);
+    }
+
+    #[test]
+    fn find_key_line_missing_key_falls_back_to_one() {
+        let contents = "[lib-one]\ngreeting = \"hi\"\n";
+        assert_eq!(find_key_line(contents, "lib-one", "buffer_size"), 1);
+    }
+
+    #[test]
+    fn parse_env_value_numbers_and_bools() {
+        assert_eq!(parse_env_value("8192"), toml::Value::Integer(8192));
+        assert_eq!(parse_env_value("1.5"), toml::Value::Float(1.5));
+        assert_eq!(parse_env_value("true"), toml::Value::Boolean(true));
+    }
+
+    #[test]
+    fn parse_env_value_quoted_string() {
+        assert_eq!(
+            parse_env_value("\"hello\""),
+            toml::Value::String("hello".to_string())
+        );
+    }
+
+    // A bare enum variant name like `Foo` isn't valid TOML on its own, so it
+    // falls back to being treated as a plain string (later turned into
+    // `Ty::Foo` by `value_to_tokens`).
+    #[test]
+    fn parse_env_value_bare_word_falls_back_to_string() {
+        assert_eq!(
+            parse_env_value("Foo"),
+            toml::Value::String("Foo".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_env_value_array() {
+        assert_eq!(
+            parse_env_value("[1, 2, 3]"),
+            toml::Value::Array(vec![1.into(), 2.into(), 3.into()])
+        );
+    }
+
+    // A fresh, empty directory under `std::env::temp_dir()`, unique per call
+    // so tests can run concurrently without stepping on each other.
+    fn temp_dir() -> PathBuf {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "toml-cfg-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn find_cfg_chain_walks_up_to_root() {
+        let root = temp_dir();
+        let nested = root.join("apps").join("my-app");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        let chain = find_cfg_chain(&nested, &root);
+        assert_eq!(chain, vec![nested.clone(), root.join("apps"), root.clone()]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn resolve_cfg_path_finds_cfg_toml() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("cfg.toml"), "").unwrap();
+
+        assert_eq!(resolve_cfg_path(&dir), Some(dir.join("cfg.toml")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve_cfg_path_none_without_cfg_toml() {
+        let dir = temp_dir();
+
+        assert_eq!(resolve_cfg_path(&dir), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // `cfg.local.toml` on its own isn't a supported config source: it exists
+    // only so its *coexistence* with `cfg.toml` can be flagged as ambiguous.
+    #[test]
+    fn resolve_cfg_path_ignores_lone_cfg_local_toml() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("cfg.local.toml"), "").unwrap();
+
+        assert_eq!(resolve_cfg_path(&dir), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "Ambiguous config sources")]
+    fn resolve_cfg_path_panics_on_ambiguity() {
+        let dir = temp_dir();
+        std::fs::write(dir.join("cfg.toml"), "").unwrap();
+        std::fs::write(dir.join("cfg.local.toml"), "").unwrap();
+
+        let _ = resolve_cfg_path(&dir);
+    }
+}